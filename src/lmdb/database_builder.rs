@@ -0,0 +1,327 @@
+
+use error_stack::Result;
+
+use super::database::Database;
+use super::database::DatabaseWriter;
+use super::error::Error;
+
+use super::merge_policy::MergePolicy;
+use super::model;
+use super::WordSize;
+
+/// Conservative (64-bit) page header size, used only to decide when a page is "full enough" to
+/// flush; the writer itself computes the exact on-disk layout.
+const LEAF_HEADER: usize = 16;
+const NODE_FIXED: usize = 8;
+const DIR_SLOT: usize = 2;
+/// Leave headroom below the real 4096 limit, mirroring LMDB's default fill factor so a bulk
+/// load doesn't pack pages so tightly that a single later insert has to split them immediately.
+const FILL_TARGET: usize = 4096 - 4096 / 4;
+
+impl<'a> Database<'a> {
+    /// Write a branch page: each entry pairs a separator key with the child page it guards,
+    /// except the first (leftmost) entry, whose key is always empty. Branch pages share the
+    /// leaf page's on-disk layout, with the child pageno stored where a leaf would store a value,
+    /// encoded at the same word width as the rest of the database.
+    pub(super) fn write_branch_unsafe<'b>(
+        writer: &'b mut (dyn DatabaseWriter + 'a),
+        pageno: usize,
+        word_size: WordSize,
+        children: Vec<(Vec<u8>, u64)>,
+    ) -> Result<(), Error> {
+        let nodes = children
+            .into_iter()
+            .map(|(key, child)| model::Node {
+                flags: 0,
+                key,
+                data: Self::encode_child_pageno(word_size, child),
+            })
+            .collect();
+
+        Self::write_leaf_unsafe(
+            writer,
+            model::Leaf {
+                pageno,
+                flags: model::header::Flags::BRANCH,
+                nodes,
+            },
+        )
+    }
+
+    /// Encode a child pageno at the database's word width, matching the width `write_word` uses
+    /// for page headers and metadata elsewhere in the format.
+    fn encode_child_pageno(word_size: WordSize, child: u64) -> Vec<u8> {
+        match word_size {
+            WordSize::Word32 => (child as u32).to_le_bytes().to_vec(),
+            WordSize::Word64 => child.to_le_bytes().to_vec(),
+        }
+    }
+
+    /// Bulk-load a sorted node stream into a fresh B-tree, the way a LevelDB `TableBuilder` or
+    /// LMDB's append mode would: fill leaf pages until nearly full, bubble a separator key and
+    /// child pageno up to the parent level, and recurse bottom-up until a single root remains.
+    /// `next_pageno` is the first page number available for data; pages are handed out
+    /// sequentially so the whole tree can be streamed to disk without buffering more than one
+    /// level of separators at a time.
+    pub fn build_tree_unsafe<'b>(
+        writer: &'b mut (dyn DatabaseWriter + 'a),
+        mut nodes: impl FnMut() -> Result<Option<model::Node>, Error>,
+        next_pageno: u64,
+        word_size: WordSize,
+        on_duplicate: MergePolicy,
+    ) -> Result<model::Database, Error> {
+        let mut next_pageno = next_pageno;
+        let mut entries = 0u64;
+        let mut leaf_pages = 0u64;
+
+        let mut level = Vec::<(Vec<u8>, u64)>::new();
+        let mut current = Vec::<model::Node>::new();
+        let mut current_size = LEAF_HEADER;
+
+        while let Some(node) = nodes()? {
+            if let Some(last) = current.last_mut() {
+                if last.key == node.key {
+                    let before = DIR_SLOT + NODE_FIXED + last.key.len() + last.data.len();
+                    on_duplicate.merge(last, node)?;
+                    let after = DIR_SLOT + NODE_FIXED + last.key.len() + last.data.len();
+                    // A merge (e.g. Concat) can grow or shrink the node in place; keep
+                    // `current_size` honest so the fill-target check before the next distinct
+                    // key still reflects what's actually accumulated in `current`.
+                    current_size = current_size + after - before;
+                    continue;
+                }
+            }
+
+            let node_size = DIR_SLOT + NODE_FIXED + node.key.len() + node.data.len();
+            if !current.is_empty() && current_size + node_size > FILL_TARGET {
+                level.push(Self::flush_leaf(writer, &mut next_pageno, &mut leaf_pages, std::mem::take(&mut current))?);
+                current_size = LEAF_HEADER;
+            }
+
+            entries += 1;
+            current_size += node_size;
+            current.push(node);
+        }
+
+        if !current.is_empty() || level.is_empty() {
+            level.push(Self::flush_leaf(writer, &mut next_pageno, &mut leaf_pages, current)?);
+        }
+
+        let mut branch_pages = 0u64;
+        let mut depth = 1u16;
+        while level.len() > 1 {
+            level = Self::build_branch_level(writer, &mut next_pageno, &mut branch_pages, word_size, level)?;
+            depth += 1;
+        }
+
+        let root = level.first().map(|(_, pageno)| *pageno).unwrap_or(next_pageno);
+
+        Ok(model::Database {
+            pad: 4096,
+            flags: model::metadata::Flags::empty(),
+            depth,
+            branch_pages,
+            leaf_pages,
+            overflow_pages: 0,
+            entries,
+            root,
+        })
+    }
+
+    fn flush_leaf<'b>(
+        writer: &'b mut (dyn DatabaseWriter + 'a),
+        next_pageno: &mut u64,
+        leaf_pages: &mut u64,
+        nodes: Vec<model::Node>,
+    ) -> Result<(Vec<u8>, u64), Error> {
+        let pageno = *next_pageno;
+        *next_pageno += 1;
+        *leaf_pages += 1;
+
+        let first_key = nodes.first().map(|node| node.key.clone()).unwrap_or_default();
+        Self::write_leaf_unsafe(
+            writer,
+            model::Leaf {
+                pageno: pageno as usize,
+                flags: model::header::Flags::LEAF,
+                nodes,
+            },
+        )?;
+
+        Ok((first_key, pageno))
+    }
+
+    fn build_branch_level<'b>(
+        writer: &'b mut (dyn DatabaseWriter + 'a),
+        next_pageno: &mut u64,
+        branch_pages: &mut u64,
+        word_size: WordSize,
+        children: Vec<(Vec<u8>, u64)>,
+    ) -> Result<Vec<(Vec<u8>, u64)>, Error> {
+        let mut level = Vec::<(Vec<u8>, u64)>::new();
+        let mut current = Vec::<(Vec<u8>, u64)>::new();
+        let mut current_size = LEAF_HEADER;
+
+        for (key, child) in children {
+            let node_size = DIR_SLOT + NODE_FIXED + key.len() + 8;
+            if !current.is_empty() && current_size + node_size > FILL_TARGET {
+                level.push(Self::flush_branch(writer, next_pageno, branch_pages, word_size, std::mem::take(&mut current))?);
+                current_size = LEAF_HEADER;
+            }
+            current_size += node_size;
+            current.push((key, child));
+        }
+        if !current.is_empty() {
+            level.push(Self::flush_branch(writer, next_pageno, branch_pages, word_size, current)?);
+        }
+
+        Ok(level)
+    }
+
+    fn flush_branch<'b>(
+        writer: &'b mut (dyn DatabaseWriter + 'a),
+        next_pageno: &mut u64,
+        branch_pages: &mut u64,
+        word_size: WordSize,
+        mut children: Vec<(Vec<u8>, u64)>,
+    ) -> Result<(Vec<u8>, u64), Error> {
+        let pageno = *next_pageno;
+        *next_pageno += 1;
+        *branch_pages += 1;
+
+        let first_key = children.first().map(|(key, _)| key.clone()).unwrap_or_default();
+        if let Some(leftmost) = children.first_mut() {
+            leftmost.0.clear();
+        }
+
+        Self::write_branch_unsafe(writer, pageno as usize, word_size, children)?;
+        Ok((first_key, pageno))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile;
+
+    use crate::lmdb::reader::Reader64;
+    use crate::lmdb::writer::Writer64;
+
+    use super::super::merge_policy::MergePolicy;
+    use super::super::model;
+    use super::super::WordSize;
+    use super::*;
+
+    /// Enough entries to overflow several leaf pages, so the tree needs a branch level above
+    /// them — this is the shape that exposed the chunk0-1 branch-decoding bug, so round-tripping
+    /// it through `check_unsafe` is the regression test for that fix too.
+    #[test]
+    fn test_build_tree_round_trip_with_branch_level() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let writer = std::io::BufWriter::new(file.reopen().unwrap());
+        let mut writer = Writer64::from(writer);
+        let dw = &mut writer;
+
+        let total = 300u32;
+        let mut next = 0u32;
+        let main_db = Database::build_tree_unsafe(
+            dw,
+            || {
+                if next >= total {
+                    return Ok(None);
+                }
+                let node = model::Node {
+                    flags: 0,
+                    key: next.to_be_bytes().to_vec(),
+                    data: vec![0u8; 16],
+                };
+                next += 1;
+                Ok(Some(node))
+            },
+            2,
+            WordSize::Word64,
+            MergePolicy::Append,
+        )
+        .unwrap();
+
+        assert!(main_db.depth >= 2, "tree with {} entries should need a branch level", total);
+        assert_eq!(main_db.entries, total as u64);
+
+        let (mut meta1, _) = Database::init_meta_unsafe(1048576).unwrap();
+        meta1.main = main_db;
+        meta1.last_pgno = meta1.main.root.max(1);
+        let meta2 = meta1.clone();
+
+        Database::write_meta_unsafe(dw, meta1, 0).unwrap();
+        Database::write_meta_unsafe(dw, meta2, 1).unwrap();
+        use std::io::Write;
+        writer.flush().unwrap();
+
+        let file = file.reopen().unwrap();
+        let reader = std::io::BufReader::new(file);
+        let mut reader = Reader64::from(reader);
+        let dr = &mut reader;
+
+        Database::check_unsafe(dr).unwrap();
+    }
+
+    /// A long run of duplicate keys merged via `Concat` grows one node well past what a single
+    /// small insert would, which previously left `current_size` understating the leaf's real
+    /// size (it was never updated after a merge) and could drive `write_leaf_unsafe`'s offset
+    /// arithmetic negative. Round-tripping through `check_unsafe` also confirms the other,
+    /// un-merged keys are still placed in a valid, readable tree alongside the big merged node.
+    #[test]
+    fn test_build_tree_tracks_size_after_concat_merge() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let writer = std::io::BufWriter::new(file.reopen().unwrap());
+        let mut writer = Writer64::from(writer);
+        let dw = &mut writer;
+
+        let dup_count = 40u32;
+        let distinct_count = 40u32;
+        let mut dup_emitted = 0u32;
+        let mut next_distinct = 0u32;
+
+        let main_db = Database::build_tree_unsafe(
+            dw,
+            || {
+                if dup_emitted < dup_count {
+                    dup_emitted += 1;
+                    return Ok(Some(model::Node { flags: 0, key: vec![0u8; 4], data: vec![7u8; 64] }));
+                }
+                if next_distinct >= distinct_count {
+                    return Ok(None);
+                }
+                next_distinct += 1;
+                Ok(Some(model::Node {
+                    flags: 0,
+                    key: next_distinct.to_be_bytes().to_vec(),
+                    data: vec![0u8; 16],
+                }))
+            },
+            2,
+            WordSize::Word64,
+            MergePolicy::Concat,
+        )
+        .unwrap();
+
+        // The duplicate run collapses into a single node, so entries = 1 merged + the distinct ones.
+        assert_eq!(main_db.entries, 1 + distinct_count as u64);
+
+        let (mut meta1, _) = Database::init_meta_unsafe(1048576).unwrap();
+        meta1.main = main_db;
+        meta1.last_pgno = meta1.main.root.max(1);
+        let meta2 = meta1.clone();
+
+        Database::write_meta_unsafe(dw, meta1, 0).unwrap();
+        Database::write_meta_unsafe(dw, meta2, 1).unwrap();
+        use std::io::Write;
+        writer.flush().unwrap();
+
+        let file = file.reopen().unwrap();
+        let reader = std::io::BufReader::new(file);
+        let mut reader = Reader64::from(reader);
+        let dr = &mut reader;
+
+        Database::check_unsafe(dr).unwrap();
+    }
+}
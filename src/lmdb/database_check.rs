@@ -0,0 +1,478 @@
+
+use error_stack::Report;
+use error_stack::Result;
+
+use std::collections::HashSet;
+
+use super::database::Database;
+use super::database::DatabaseReader;
+use super::error::Error;
+
+use super::model;
+use super::model::header;
+
+#[derive(Debug, Default)]
+struct TreeCounts {
+    leaf_pages: u64,
+    branch_pages: u64,
+    overflow_pages: u64,
+    entries: u64,
+}
+
+impl<'a> Database<'a> {
+    /// Walk the whole file the way `thin_check` walks thin metadata: pick the newest meta page,
+    /// then visit every reachable page and collect every discrepancy found instead of stopping
+    /// at the first one, so the caller gets a full damage report.
+    pub fn check_unsafe<'b>(reader: &'b mut (dyn DatabaseReader + 'a)) -> Result<(), Error> {
+        let meta = Self::pick_meta_unsafe(reader)?;
+
+        let mut issues = Vec::<String>::new();
+        let mut counts = TreeCounts::default();
+        if meta.main.root != 0 {
+            let mut seen = HashSet::<u64>::new();
+            Self::check_page_unsafe(reader, meta.main.root, &mut issues, &mut counts, &mut seen)?;
+        }
+
+        if counts.leaf_pages != meta.main.leaf_pages {
+            issues.push(format!(
+                "main: leaf_pages mismatch: meta says {}, counted {}",
+                meta.main.leaf_pages, counts.leaf_pages
+            ));
+        }
+        if counts.branch_pages != meta.main.branch_pages {
+            issues.push(format!(
+                "main: branch_pages mismatch: meta says {}, counted {}",
+                meta.main.branch_pages, counts.branch_pages
+            ));
+        }
+        if counts.overflow_pages != meta.main.overflow_pages {
+            issues.push(format!(
+                "main: overflow_pages mismatch: meta says {}, counted {}",
+                meta.main.overflow_pages, counts.overflow_pages
+            ));
+        }
+        if counts.entries != meta.main.entries {
+            issues.push(format!(
+                "main: entries mismatch: meta says {}, counted {}",
+                meta.main.entries, counts.entries
+            ));
+        }
+
+        let mut free_counts = TreeCounts::default();
+        let mut seen_free_pages = HashSet::<u64>::new();
+        Self::check_free_unsafe(
+            reader,
+            meta.free.root,
+            meta.last_pgno,
+            &mut issues,
+            &mut seen_free_pages,
+            &mut free_counts,
+        )?;
+
+        Self::finish_check(issues)
+    }
+
+    fn finish_check(issues: Vec<String>) -> Result<(), Error> {
+        let mut iter = issues.into_iter();
+        let first = match iter.next() {
+            Some(first) => first,
+            None => return Ok(()),
+        };
+
+        let mut report = Report::new(Error::default()).attach_printable(first);
+        for issue in iter {
+            report = report.attach_printable(issue);
+        }
+        Err(report)
+    }
+
+    /// Recursively validate one page and its children, accumulating problems into `issues`
+    /// rather than bailing out on the first bad page. `seen` guards against a corrupt branch
+    /// child pointing back at an ancestor (or itself), which would otherwise recurse forever.
+    fn check_page_unsafe<'b>(
+        reader: &'b mut (dyn DatabaseReader + 'a),
+        pageno: u64,
+        issues: &mut Vec<String>,
+        counts: &mut TreeCounts,
+        seen: &mut HashSet<u64>,
+    ) -> Result<(), Error> {
+        if !seen.insert(pageno) {
+            issues.push(format!("page {}: already visited (cycle in the tree)", pageno));
+            return Ok(());
+        }
+
+        Self::seek_page_unsafe(reader, pageno as usize)?;
+        let head = reader.pos()?;
+
+        let _pageno = reader.read_word()?;
+        let _pad = reader.read_u16()?;
+        let flags = header::Flags::from_bits_truncate(reader.read_u16()?);
+        let free_lower = reader.read_u16()?;
+        let free_upper = reader.read_u16()?;
+
+        if free_lower > free_upper || free_upper > 4096 {
+            issues.push(format!(
+                "page {}: free_lower {} > free_upper {} (or free_upper > 4096)",
+                pageno, free_lower, free_upper
+            ));
+            return Ok(());
+        }
+
+        if flags.contains(header::Flags::OVERFLOW) {
+            counts.overflow_pages += 1;
+            return Ok(());
+        }
+
+        if !(flags.contains(header::Flags::LEAF) || flags.contains(header::Flags::BRANCH)) {
+            issues.push(format!("page {}: unrecognised flags {:?}", pageno, flags));
+            return Ok(());
+        }
+
+        let dir_start = reader.pos()?;
+        let nptrs = (free_lower as usize).saturating_sub(dir_start - head) / 2;
+
+        let mut ptrs = Vec::<u16>::with_capacity(nptrs);
+        for _ in 0..nptrs {
+            let ptr = reader.read_u16()?;
+            if (ptr as usize) < free_upper as usize || ptr >= 4096 {
+                issues.push(format!(
+                    "page {}: node pointer {} outside [{}, 4096)",
+                    pageno, ptr, free_upper
+                ));
+            }
+            ptrs.push(ptr);
+        }
+
+        let mut last_key: Option<Vec<u8>> = None;
+        let mut children = Vec::<u64>::new();
+        for ptr in ptrs {
+            if reader.seek(std::io::SeekFrom::Start((head + ptr as usize) as u64)).is_err() {
+                issues.push(format!("page {}: failed to seek to node at {}", pageno, ptr));
+                continue;
+            }
+            let data_len = match reader.read_u32() {
+                Ok(v) => v as usize,
+                Err(_) => {
+                    issues.push(format!("page {}: failed to read node header at {}", pageno, ptr));
+                    continue;
+                }
+            };
+            let node_flags = match reader.read_u16() {
+                Ok(v) => v,
+                Err(_) => {
+                    issues.push(format!("page {}: failed to read node flags at {}", pageno, ptr));
+                    continue;
+                }
+            };
+            let key_len = match reader.read_u16() {
+                Ok(v) => v as usize,
+                Err(_) => {
+                    issues.push(format!("page {}: failed to read node key length at {}", pageno, ptr));
+                    continue;
+                }
+            };
+
+            // Bound-check the node before reading any attacker/corruption-controlled number of
+            // bytes for it: `data_len` is a raw u32 straight off a possibly-corrupt page.
+            if (ptr as usize) + 8 + key_len + data_len > 4096 {
+                issues.push(format!(
+                    "page {}: node at {} overruns the page (key {} + data {})",
+                    pageno, ptr, key_len, data_len
+                ));
+                continue;
+            }
+
+            let key = match reader.read_exact(key_len) {
+                Ok(key) => key,
+                Err(_) => {
+                    issues.push(format!("page {}: failed to read key at {}", pageno, ptr));
+                    continue;
+                }
+            };
+            let data = match reader.read_exact(data_len) {
+                Ok(data) => data,
+                Err(_) => {
+                    issues.push(format!("page {}: failed to read data at {}", pageno, ptr));
+                    continue;
+                }
+            };
+
+            if let Some(prev) = &last_key {
+                if &key >= prev {
+                    issues.push(format!(
+                        "page {}: keys are not strictly ascending at pointer {}",
+                        pageno, ptr
+                    ));
+                }
+            }
+            last_key = Some(key);
+
+            if flags.contains(header::Flags::BRANCH) {
+                let _ = node_flags;
+                match data.len() {
+                    4 => children.push(u32::from_le_bytes(data.try_into().unwrap()) as u64),
+                    8 => children.push(u64::from_le_bytes(data.try_into().unwrap())),
+                    _ => issues.push(format!(
+                        "page {}: branch node at {} has a malformed child pointer ({} bytes)",
+                        pageno, ptr, data.len()
+                    )),
+                }
+            } else {
+                counts.entries += 1;
+            }
+        }
+
+        if flags.contains(header::Flags::LEAF) {
+            counts.leaf_pages += 1;
+        } else {
+            counts.branch_pages += 1;
+            for child in children {
+                Self::check_page_unsafe(reader, child, issues, counts, seen)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Walk the `free` DB the same way, additionally checking that no page number is handed out
+    /// twice and that each referenced page is within `last_pgno`. These two checks cover the page
+    /// numbers stored in the root page's entries only; entries on deeper leaves of a multi-level
+    /// free DB are structure-checked but their page numbers are not.
+    fn check_free_unsafe<'b>(
+        reader: &'b mut (dyn DatabaseReader + 'a),
+        root: u64,
+        last_pgno: u64,
+        issues: &mut Vec<String>,
+        seen: &mut HashSet<u64>,
+        counts: &mut TreeCounts,
+    ) -> Result<(), Error> {
+        if root == 0 {
+            // no free DB yet, nothing to walk
+            return Ok(());
+        }
+
+        let mut visited_pages = HashSet::<u64>::new();
+        Self::check_page_unsafe(reader, root, issues, counts, &mut visited_pages)?;
+
+        Self::walk_free_entries_unsafe(reader, root, last_pgno, issues, seen)
+    }
+
+    fn walk_free_entries_unsafe<'b>(
+        reader: &'b mut (dyn DatabaseReader + 'a),
+        pageno: u64,
+        last_pgno: u64,
+        issues: &mut Vec<String>,
+        seen: &mut HashSet<u64>,
+    ) -> Result<(), Error> {
+        Self::seek_page_unsafe(reader, pageno as usize)?;
+        let leaf = match Self::read_leaf_unsafe(reader) {
+            Ok(leaf) => leaf,
+            Err(_) => return Ok(()),
+        };
+
+        for node in leaf.nodes {
+            for chunk in node.data.chunks(8) {
+                if chunk.len() != 8 {
+                    continue;
+                }
+                let freed = u64::from_le_bytes(chunk.try_into().unwrap());
+                if freed > last_pgno {
+                    issues.push(format!(
+                        "free db: page {} exceeds last_pgno {}",
+                        freed, last_pgno
+                    ));
+                }
+                if !seen.insert(freed) {
+                    issues.push(format!("free db: page {} referenced twice", freed));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile;
+
+    use crate::lmdb::factory::Factory;
+    use crate::lmdb::reader::Reader64;
+    use crate::lmdb::writer::Writer64;
+
+    use super::*;
+    use super::super::model;
+
+    #[test]
+    fn test_check_empty_database() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let writer = std::io::BufWriter::new(file.reopen().unwrap());
+        let mut writer = Writer64::from(writer);
+        let dw = &mut writer;
+
+        let (meta1, meta2) = Database::init_meta_unsafe(1048576).unwrap();
+        Database::write_meta_unsafe(dw, meta1, 0).unwrap();
+        Database::write_meta_unsafe(dw, meta2, 1).unwrap();
+        use std::io::Write;
+        writer.flush().unwrap();
+
+        let file = file.reopen().unwrap();
+        let reader = std::io::BufReader::new(file);
+        let mut reader = Reader64::from(reader);
+        let dr = &mut reader;
+
+        Database::check_unsafe(dr).unwrap();
+    }
+
+    #[test]
+    fn test_check_populated_database() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let writer = std::io::BufWriter::new(file.reopen().unwrap());
+        let mut writer = Writer64::from(writer);
+        let dw = &mut writer;
+
+        let mut nodes = Vec::<model::Node>::new();
+        for i in 1..3 {
+            nodes.push(model::Node {
+                flags: 0,
+                key: vec![i; 1],
+                data: vec![2 * i; 1],
+            });
+        }
+
+        let (mut meta1, mut meta2) = Database::init_meta_unsafe(1048576).unwrap();
+        meta1.main.root = 2;
+        meta1.main.leaf_pages = 1;
+        meta1.main.depth = 1;
+        meta1.main.entries = nodes.len() as u64;
+        meta1.last_pgno = 2;
+        meta2 = meta1.clone();
+
+        Database::write_meta_unsafe(dw, meta1, 0).unwrap();
+        Database::write_meta_unsafe(dw, meta2, 1).unwrap();
+        Database::write_leaf_unsafe(
+            dw,
+            model::Leaf {
+                pageno: 2,
+                flags: model::header::Flags::LEAF,
+                nodes,
+            },
+        )
+        .unwrap();
+        use std::io::Write;
+        writer.flush().unwrap();
+
+        let file = file.reopen().unwrap();
+        let reader = std::io::BufReader::new(file);
+        let mut reader = Reader64::from(reader);
+        let dr = &mut reader;
+
+        Database::check_unsafe(dr).unwrap();
+    }
+
+    /// A corrupt page whose node directory points at a node with a huge `data_len` must be
+    /// reported as an issue and not attempted, rather than allocating/reading an
+    /// attacker-controlled byte count or aborting the whole walk via `?`.
+    #[test]
+    fn test_check_reports_oversized_node_without_aborting() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        {
+            use std::io::Seek;
+            use std::io::SeekFrom;
+            use std::io::Write;
+
+            let mut f = file.reopen().unwrap();
+            f.set_len(3 * 4096).unwrap();
+
+            let (mut meta1, _) = Database::init_meta_unsafe(1048576).unwrap();
+            meta1.main.root = 2;
+            meta1.main.leaf_pages = 1;
+            meta1.main.depth = 1;
+            meta1.main.entries = 1;
+            meta1.last_pgno = 2;
+            let meta2 = meta1.clone();
+
+            let mut writer = Writer64::from(std::io::BufWriter::new(f.try_clone().unwrap()));
+            Database::write_meta_unsafe(&mut writer, meta1, 0).unwrap();
+            Database::write_meta_unsafe(&mut writer, meta2, 1).unwrap();
+            writer.flush().unwrap();
+
+            // Hand-craft page 2 as a leaf with one directory entry whose data_len is corrupt
+            // (huge); `write_leaf_unsafe` would never produce this, simulating on-disk damage.
+            let mut page = vec![0u8; 4096];
+            page[0..8].copy_from_slice(&2u64.to_le_bytes()); // pageno
+            page[8..10].copy_from_slice(&0u16.to_le_bytes()); // pad
+            page[10..12].copy_from_slice(&model::header::Flags::LEAF.bits().to_le_bytes()); // flags
+            page[12..14].copy_from_slice(&18u16.to_le_bytes()); // free_lower
+            page[14..16].copy_from_slice(&4000u16.to_le_bytes()); // free_upper
+            page[16..18].copy_from_slice(&4000u16.to_le_bytes()); // node pointer
+            page[4000..4004].copy_from_slice(&0xFFFF_FFFFu32.to_le_bytes()); // corrupt data_len
+            page[4004..4006].copy_from_slice(&0u16.to_le_bytes()); // node_flags
+            page[4006..4008].copy_from_slice(&1u16.to_le_bytes()); // key_len
+            page[4008] = 7; // key byte
+
+            f.seek(SeekFrom::Start(2 * 4096)).unwrap();
+            f.write_all(&page).unwrap();
+        }
+
+        let file = file.reopen().unwrap();
+        let reader = std::io::BufReader::new(file);
+        let mut reader = Reader64::from(reader);
+        let dr = &mut reader;
+
+        let report = Database::check_unsafe(dr).expect_err("corrupt node should be reported, not panic");
+        assert!(format!("{:?}", report).contains("overruns"));
+    }
+
+    /// A branch page whose only child points back at itself must be reported as a cycle instead
+    /// of recursing forever.
+    #[test]
+    fn test_check_reports_self_referencing_branch_without_recursing_forever() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        {
+            use std::io::Seek;
+            use std::io::SeekFrom;
+            use std::io::Write;
+
+            let mut f = file.reopen().unwrap();
+            f.set_len(3 * 4096).unwrap();
+
+            let (mut meta1, _) = Database::init_meta_unsafe(1048576).unwrap();
+            meta1.main.root = 2;
+            meta1.main.branch_pages = 1;
+            meta1.main.depth = 2;
+            meta1.last_pgno = 2;
+            let meta2 = meta1.clone();
+
+            let mut writer = Writer64::from(std::io::BufWriter::new(f.try_clone().unwrap()));
+            Database::write_meta_unsafe(&mut writer, meta1, 0).unwrap();
+            Database::write_meta_unsafe(&mut writer, meta2, 1).unwrap();
+            writer.flush().unwrap();
+
+            // Hand-craft page 2 as a branch whose single (empty-key) child points back at page
+            // 2 itself, the shape that would stack-overflow an unguarded recursive walk.
+            let mut page = vec![0u8; 4096];
+            page[0..8].copy_from_slice(&2u64.to_le_bytes()); // pageno
+            page[8..10].copy_from_slice(&0u16.to_le_bytes()); // pad
+            page[10..12].copy_from_slice(&model::header::Flags::BRANCH.bits().to_le_bytes());
+            page[12..14].copy_from_slice(&18u16.to_le_bytes()); // free_lower
+            page[14..16].copy_from_slice(&4080u16.to_le_bytes()); // free_upper
+            page[16..18].copy_from_slice(&4080u16.to_le_bytes()); // node pointer
+            page[4080..4084].copy_from_slice(&8u32.to_le_bytes()); // data_len (8-byte pageno)
+            page[4084..4086].copy_from_slice(&0u16.to_le_bytes()); // node_flags
+            page[4086..4088].copy_from_slice(&0u16.to_le_bytes()); // key_len (leftmost, empty key)
+            page[4088..4096].copy_from_slice(&2u64.to_le_bytes()); // child pageno = itself
+
+            f.seek(SeekFrom::Start(2 * 4096)).unwrap();
+            f.write_all(&page).unwrap();
+        }
+
+        let file = file.reopen().unwrap();
+        let reader = std::io::BufReader::new(file);
+        let mut reader = Reader64::from(reader);
+        let dr = &mut reader;
+
+        let report = Database::check_unsafe(dr).expect_err("a self-referencing branch should be reported, not recurse forever");
+        assert!(format!("{:?}", report).contains("cycle"));
+    }
+}
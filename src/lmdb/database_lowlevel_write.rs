@@ -14,12 +14,12 @@ use super::model;
 use super::model::metadata; 
 
 impl<'a> Database<'a> {
-    pub(super) fn init_meta_unsafe() -> Result<(model::Metadata, model::Metadata), Error> {
+    pub(super) fn init_meta_unsafe(mapsize: u64) -> Result<(model::Metadata, model::Metadata), Error> {
         let meta = model::Metadata {
             magic: lowlevel::MAGIC,
             version: lowlevel::VERSION,
             address: 0,
-            mapsize: 1048576, // Do know what this is
+            mapsize,
             main: model::Database {
                 pad: 4096,
                 flags: model::metadata::Flags::empty(),
@@ -137,6 +137,29 @@ impl<'a> Database<'a> {
         Ok(())
     }
 
+    /// Commit `meta` the way real LMDB does: data/leaf pages are assumed already written by the
+    /// caller, so this only has to fsync them, bump `txnid`, write the result into the slot other
+    /// than `current_slot` (the caller names the slot holding the newest meta, so the other one is
+    /// the older), then fsync again. A crash between the two fsyncs
+    /// leaves the untouched slot with the previous, still-consistent `txnid`, so `pick_meta_unsafe`
+    /// always finds a valid snapshot to hand readers.
+    pub(super) fn commit_meta_unsafe<'b>(
+        writer: &'b mut (dyn DatabaseWriter + 'a),
+        current: model::Metadata,
+        current_slot: usize,
+    ) -> Result<(), Error> {
+        writer.sync()?;
+
+        let mut next = current.clone();
+        next.txnid = current.txnid + 1;
+        let next_slot = 1 - current_slot;
+
+        Self::write_meta_unsafe(writer, next, next_slot)?;
+        writer.sync()?;
+
+        Ok(())
+    }
+
 }
 
 #[cfg(test)]
@@ -174,6 +197,31 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_commit_meta_rotates_slots() {
+        setup();
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let writer = std::io::BufWriter::new(file.reopen().unwrap());
+        let mut writer = Writer64::from(writer);
+        let dw = &mut writer;
+
+        let (meta1, meta2) = Database::init_meta_unsafe(1048576).unwrap();
+        Database::write_meta_unsafe(dw, meta1.clone(), 0).unwrap();
+        Database::write_meta_unsafe(dw, meta2, 1).unwrap();
+
+        // Slot 0 currently holds the newest txnid, so the next commit must land in slot 1.
+        Database::commit_meta_unsafe(dw, meta1, 0).unwrap();
+        writer.flush().unwrap();
+
+        let file = file.reopen().unwrap();
+        let reader = std::io::BufReader::new(file);
+        let mut reader = Reader64::from(reader);
+        let dr = &mut reader;
+
+        let meta = Database::pick_meta_unsafe(dr).unwrap();
+        assert_eq!(meta.txnid, 1);
+    }
+
     #[test]
     fn test_write_meta_64() {
         setup();
@@ -182,7 +230,7 @@ mod tests {
         let mut writer = Writer64::from(writer);
         let dw = &mut writer;
 
-        let (meta1, meta2) = Database::init_meta_unsafe().unwrap();
+        let (meta1, meta2) = Database::init_meta_unsafe(1048576).unwrap();
         Database::write_meta_unsafe(dw, meta1, 0).unwrap();
         Database::write_meta_unsafe(dw, meta2, 1).unwrap();
         writer.flush().unwrap();
@@ -205,7 +253,7 @@ mod tests {
         let mut writer = Writer64::from(writer);
         let dw = &mut writer;
 
-        let (meta1, meta2) = Database::init_meta_unsafe().unwrap();
+        let (meta1, meta2) = Database::init_meta_unsafe(1048576).unwrap();
         Database::write_meta_unsafe(dw, meta1, 0).unwrap();
         Database::write_meta_unsafe(dw, meta2, 1).unwrap();
 
@@ -243,7 +291,7 @@ mod tests {
         let mut writer = Writer32::from(writer);
         let dw = &mut writer;
 
-        let (meta1, meta2) = Database::init_meta_unsafe().unwrap();
+        let (meta1, meta2) = Database::init_meta_unsafe(1048576).unwrap();
         Database::write_meta_unsafe(dw, meta1, 0).unwrap();
         Database::write_meta_unsafe(dw, meta2, 1).unwrap();
 
@@ -0,0 +1,243 @@
+
+use error_stack::Result;
+
+use super::database::Database;
+use super::database::DatabaseReader;
+use super::database::DatabaseWriter;
+use super::error::Error;
+
+use super::merge_policy::MergePolicy;
+use super::model;
+use super::model::header;
+use super::WordSize;
+
+impl<'a> Database<'a> {
+    /// Salvage a file whose meta pages are corrupt, the way `thin_repair` salvages thin
+    /// metadata: linearly scan every 4096-byte stride, keep whatever parses cleanly as a leaf,
+    /// pick the run of leaves with globally ascending keys that holds the most entries, then rebuild a fresh,
+    /// properly linked tree over their nodes via `build_tree_unsafe` (rather than pointing
+    /// `root` at only the first salvaged leaf and leaving the rest unreachable). The new tree's
+    /// pages are written to `writer`, which should target a brand-new output file; the damaged
+    /// original is never touched.
+    pub fn repair_unsafe<'b>(
+        reader: &'b mut (dyn DatabaseReader + 'a),
+        writer: &'b mut (dyn DatabaseWriter + 'a),
+        file_pages: u64,
+        word_size: WordSize,
+    ) -> Result<model::Metadata, Error> {
+        let mut candidates = Vec::<(u64, model::Leaf)>::new();
+        for pageno in 0..file_pages {
+            if let Some(leaf) = Self::try_parse_leaf_unsafe(reader, pageno) {
+                candidates.push((pageno, leaf));
+            }
+        }
+
+        let chosen = Self::pick_consistent_unsafe(candidates);
+
+        let entries: u64 = chosen.iter().map(|(_, leaf)| leaf.nodes.len() as u64).sum();
+        let avg_key = chosen.iter().flat_map(|(_, leaf)| leaf.nodes.iter().map(|n| n.key.len())).sum::<usize>().checked_div(entries as usize).unwrap_or(0);
+        let avg_val = chosen.iter().flat_map(|(_, leaf)| leaf.nodes.iter().map(|n| n.data.len())).sum::<usize>().checked_div(entries as usize).unwrap_or(0);
+
+        let mut nodes = chosen.into_iter().flat_map(|(_, leaf)| leaf.nodes.into_iter());
+        let main_db = Self::build_tree_unsafe(writer, || Ok(nodes.next()), 2, word_size, MergePolicy::KeepFirst)?;
+
+        let mapsize = super::metadata_size::estimate_mapsize(main_db.entries, avg_key, avg_val).max((main_db.root + 1) * 4096);
+
+        let (mut meta, _) = Self::init_meta_unsafe(mapsize)?;
+        meta.main = main_db;
+        meta.last_pgno = meta.main.root.max(1);
+
+        Ok(meta)
+    }
+
+    /// Write the recovered metadata into a brand-new file; the damaged original is never
+    /// touched. An empty genesis meta goes into slot 0, then the recovered metadata is committed
+    /// into slot 1 with `txnid` bumped, the same double-buffered commit every other writer uses.
+    pub fn write_repaired_unsafe<'b>(
+        writer: &'b mut (dyn DatabaseWriter + 'a),
+        meta: model::Metadata,
+    ) -> Result<(), Error> {
+        let genesis = {
+            let mut genesis = meta.clone();
+            genesis.main = model::Database {
+                pad: 4096,
+                flags: model::metadata::Flags::empty(),
+                depth: 0,
+                branch_pages: 0,
+                leaf_pages: 0,
+                overflow_pages: 0,
+                entries: 0,
+                root: 0,
+            };
+            genesis.last_pgno = 0;
+            genesis.txnid = 0;
+            genesis
+        };
+
+        Self::write_meta_unsafe(writer, genesis, 0)?;
+        Self::commit_meta_unsafe(writer, meta, 0)?;
+        Ok(())
+    }
+
+    /// Parse a candidate leaf page, the same way `database_check::check_page_unsafe` validates
+    /// one: a page whose header merely looks plausible can still have garbage node pointers or
+    /// out-of-order keys, and `Repair` runs specifically over corrupt/garbage files, so every
+    /// pointer must be bounds-checked and every key ordering verified before the page is trusted
+    /// at all (as opposed to handing raw, unvalidated bytes straight to `read_leaf_unsafe`).
+    fn try_parse_leaf_unsafe<'b>(
+        reader: &'b mut (dyn DatabaseReader + 'a),
+        pageno: u64,
+    ) -> Option<model::Leaf> {
+        Self::seek_page_unsafe(reader, pageno as usize).ok()?;
+        let head = reader.pos().ok()?;
+
+        let _pageno = reader.read_word().ok()?;
+        let _pad = reader.read_u16().ok()?;
+        let flags = header::Flags::from_bits_truncate(reader.read_u16().ok()?);
+        if !flags.contains(header::Flags::LEAF) {
+            return None;
+        }
+        let free_lower = reader.read_u16().ok()?;
+        let free_upper = reader.read_u16().ok()?;
+        if free_lower > free_upper || free_upper > 4096 {
+            return None;
+        }
+
+        let dir_start = reader.pos().ok()?;
+        let nptrs = (free_lower as usize).saturating_sub(dir_start - head) / 2;
+
+        let mut ptrs = Vec::<u16>::with_capacity(nptrs);
+        for _ in 0..nptrs {
+            let ptr = reader.read_u16().ok()?;
+            if (ptr as usize) < free_upper as usize || ptr >= 4096 {
+                return None;
+            }
+            ptrs.push(ptr);
+        }
+
+        let mut last_key: Option<Vec<u8>> = None;
+        for &ptr in &ptrs {
+            reader.seek(std::io::SeekFrom::Start((head + ptr as usize) as u64)).ok()?;
+            let data_len = reader.read_u32().ok()? as usize;
+            let _node_flags = reader.read_u16().ok()?;
+            let key_len = reader.read_u16().ok()? as usize;
+
+            if ptr as usize + 8 + key_len + data_len > 4096 {
+                return None;
+            }
+
+            let key = reader.read_exact(key_len).ok()?;
+            if let Some(prev) = &last_key {
+                if &key <= prev {
+                    return None;
+                }
+            }
+            last_key = Some(key);
+        }
+
+        Self::seek_page_unsafe(reader, pageno as usize).ok()?;
+        Self::read_leaf_unsafe(reader).ok()
+    }
+
+    fn pick_consistent_unsafe(mut candidates: Vec<(u64, model::Leaf)>) -> Vec<(u64, model::Leaf)> {
+        candidates.sort_by(|a, b| {
+            let ka = a.1.nodes.first().map(|n| n.key.clone()).unwrap_or_default();
+            let kb = b.1.nodes.first().map(|n| n.key.clone()).unwrap_or_default();
+            ka.cmp(&kb)
+        });
+
+        let mut best = Vec::<(u64, model::Leaf)>::new();
+        let mut current = Vec::<(u64, model::Leaf)>::new();
+        let mut last_key: Option<Vec<u8>> = None;
+
+        for (pageno, leaf) in candidates {
+            let first_key = leaf.nodes.first().map(|n| n.key.clone());
+            let fits = match (&last_key, &first_key) {
+                (Some(last), Some(first)) => first > last,
+                _ => true,
+            };
+
+            if !fits {
+                if Self::entry_count(&current) > Self::entry_count(&best) {
+                    best = std::mem::take(&mut current);
+                } else {
+                    current.clear();
+                }
+            }
+
+            last_key = leaf.nodes.last().map(|n| n.key.clone()).or(last_key);
+            current.push((pageno, leaf));
+        }
+
+        if Self::entry_count(&current) > Self::entry_count(&best) {
+            best = current;
+        }
+        best
+    }
+
+    fn entry_count(set: &[(u64, model::Leaf)]) -> usize {
+        set.iter().map(|(_, leaf)| leaf.nodes.len()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile;
+
+    use crate::lmdb::reader::Reader64;
+    use crate::lmdb::writer::Writer64;
+
+    use super::*;
+
+    /// Two salvaged leaves at arbitrary, non-adjacent pagenos (as a linear corruption scan would
+    /// find them) must both end up reachable from the repaired tree's root, not just the first.
+    #[test]
+    fn test_repair_links_all_salvaged_leaves_into_one_reachable_tree() {
+        let src = tempfile::NamedTempFile::new().unwrap();
+        {
+            let mut writer = Writer64::from(std::io::BufWriter::new(src.reopen().unwrap()));
+            Database::write_leaf_unsafe(
+                &mut writer,
+                model::Leaf {
+                    pageno: 5,
+                    flags: header::Flags::LEAF,
+                    nodes: vec![
+                        model::Node { flags: 0, key: vec![1], data: vec![10] },
+                        model::Node { flags: 0, key: vec![2], data: vec![20] },
+                    ],
+                },
+            )
+            .unwrap();
+            Database::write_leaf_unsafe(
+                &mut writer,
+                model::Leaf {
+                    pageno: 9,
+                    flags: header::Flags::LEAF,
+                    nodes: vec![model::Node { flags: 0, key: vec![3], data: vec![30] }],
+                },
+            )
+            .unwrap();
+            use std::io::Write;
+            writer.flush().unwrap();
+        }
+
+        let file = src.reopen().unwrap();
+        let reader = std::io::BufReader::new(file);
+        let mut reader = Reader64::from(reader);
+
+        let out = tempfile::NamedTempFile::new().unwrap();
+        let mut out_writer = Writer64::from(std::io::BufWriter::new(out.reopen().unwrap()));
+
+        let meta = Database::repair_unsafe(&mut reader, &mut out_writer, 10, WordSize::Word64).unwrap();
+        assert_eq!(meta.main.entries, 3);
+
+        Database::write_repaired_unsafe(&mut out_writer, meta).unwrap();
+        use std::io::Write;
+        out_writer.flush().unwrap();
+
+        let out_file = out.reopen().unwrap();
+        let out_reader = std::io::BufReader::new(out_file);
+        let mut out_reader = Reader64::from(out_reader);
+        Database::check_unsafe(&mut out_reader).unwrap();
+    }
+}
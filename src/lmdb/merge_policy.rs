@@ -0,0 +1,75 @@
+
+use error_stack::Report;
+use error_stack::Result;
+
+use super::error::Error;
+use super::model;
+
+/// What to do when two consecutive sorted nodes share a key, mirroring the Append-vs-
+/// GetMergePut distinction Meilisearch's LMDB indexer makes. `Append` is the strict default:
+/// it assumes the input is already deduplicated and treats a collision as corrupt input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum MergePolicy {
+    #[default]
+    Append,
+    KeepFirst,
+    KeepLast,
+    Concat,
+}
+
+impl MergePolicy {
+    /// Fold `incoming` into `existing`, both sharing the same key, according to this policy.
+    pub(super) fn merge(self, existing: &mut model::Node, incoming: model::Node) -> Result<(), Error> {
+        match self {
+            MergePolicy::Append => {
+                return Err(Report::new(Error::default())
+                    .attach_printable(format!("duplicate key under Append merge policy: {:?}", existing.key)));
+            }
+            MergePolicy::KeepFirst => {}
+            MergePolicy::KeepLast => {
+                existing.flags = incoming.flags;
+                existing.data = incoming.data;
+            }
+            MergePolicy::Concat => {
+                existing.data.extend_from_slice(&incoming.data);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(data: &[u8]) -> model::Node {
+        model::Node { flags: 0, key: vec![1], data: data.to_vec() }
+    }
+
+    #[test]
+    fn test_append_rejects_duplicate() {
+        let mut existing = node(b"a");
+        assert!(MergePolicy::Append.merge(&mut existing, node(b"b")).is_err());
+    }
+
+    #[test]
+    fn test_keep_first_ignores_incoming() {
+        let mut existing = node(b"a");
+        MergePolicy::KeepFirst.merge(&mut existing, node(b"b")).unwrap();
+        assert_eq!(existing.data, b"a");
+    }
+
+    #[test]
+    fn test_keep_last_replaces_existing() {
+        let mut existing = node(b"a");
+        MergePolicy::KeepLast.merge(&mut existing, node(b"b")).unwrap();
+        assert_eq!(existing.data, b"b");
+    }
+
+    #[test]
+    fn test_concat_appends_incoming() {
+        let mut existing = node(b"a");
+        MergePolicy::Concat.merge(&mut existing, node(b"b")).unwrap();
+        assert_eq!(existing.data, b"ab");
+    }
+}
@@ -0,0 +1,74 @@
+
+const PAGE_SIZE: usize = 4096;
+/// Number of bytes a page header plus its two length fields take up before the node pointer
+/// directory starts (word-size independent at the estimation level: 64-bit headers are worst
+/// case, so that's what's budgeted here).
+const PAGE_HEADER: usize = 16;
+const DIR_SLOT: usize = 2;
+const NODE_OVERHEAD: usize = 8;
+const BRANCH_CHILD_OVERHEAD: usize = 8;
+
+/// Estimate how many 4096-byte pages a database holding `entries` key/value pairs of the given
+/// average sizes will need, the way `thin_metadata_size` estimates thin metadata: pack as many
+/// entries per leaf as fit, then sum branch levels bottom-up using the branch fanout until a
+/// single root remains, plus overflow pages for values that don't fit on one page.
+pub fn estimate_pages(entries: u64, avg_key_size: usize, avg_val_size: usize) -> u64 {
+    let usable = PAGE_SIZE - PAGE_HEADER;
+
+    let per_leaf_entry = DIR_SLOT + NODE_OVERHEAD + avg_key_size + avg_val_size.min(usable);
+    let entries_per_leaf = (usable / per_leaf_entry).max(1);
+    let leaf_pages = div_ceil(entries, entries_per_leaf as u64).max(1);
+
+    let per_branch_entry = DIR_SLOT + NODE_OVERHEAD + avg_key_size + BRANCH_CHILD_OVERHEAD;
+    let branch_fanout = (usable / per_branch_entry).max(2) as u64;
+
+    let mut level_pages = leaf_pages;
+    let mut total_pages = leaf_pages;
+    while level_pages > 1 {
+        level_pages = div_ceil(level_pages, branch_fanout);
+        total_pages += level_pages;
+    }
+
+    let overflow_pages = if avg_val_size > usable {
+        entries * div_ceil(avg_val_size as u64, PAGE_SIZE as u64)
+    } else {
+        0
+    };
+
+    // Two meta pages plus the free-list root, so an estimate can be handed straight to
+    // `init_meta_unsafe` as a usable `mapsize`.
+    total_pages + overflow_pages + 3
+}
+
+/// Round an estimated page count up to a `mapsize` in bytes.
+pub fn estimate_mapsize(entries: u64, avg_key_size: usize, avg_val_size: usize) -> u64 {
+    estimate_pages(entries, avg_key_size, avg_val_size) * PAGE_SIZE as u64
+}
+
+fn div_ceil(a: u64, b: u64) -> u64 {
+    (a + b - 1) / b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_small_database_fits_in_one_leaf() {
+        let pages = estimate_pages(10, 8, 8);
+        assert_eq!(pages, 1 + 3);
+    }
+
+    #[test]
+    fn test_large_database_needs_branch_levels() {
+        let pages = estimate_pages(1_000_000, 16, 16);
+        assert!(pages > 1_000);
+    }
+
+    #[test]
+    fn test_oversized_values_add_overflow_pages() {
+        let with_overflow = estimate_pages(10, 8, 8192);
+        let without_overflow = estimate_pages(10, 8, 8);
+        assert!(with_overflow > without_overflow);
+    }
+}
@@ -0,0 +1,172 @@
+
+use std::collections::BTreeSet;
+use std::io::Read;
+use std::io::Seek;
+use std::io::Write;
+
+use error_stack::Report;
+use error_stack::Result;
+use error_stack::ResultExt;
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use super::database::Database;
+use super::database::DatabaseReader;
+use super::error::Error;
+use super::model::header;
+use super::WordSize;
+
+/// Identifies a pack stream so `unpack` can refuse to inflate an unrelated file.
+const PACK_MAGIC: u32 = 0x504c_4d4b;
+
+impl<'a> Database<'a> {
+    /// Determine exactly which pages are live, modelled on `thin_metadata_pack`: walk the main
+    /// B-tree and the free DB from their meta roots and collect every page visited. Real files
+    /// are sized to `mapsize` but are usually mostly empty, so packing only these pages instead
+    /// of the whole file gives a dramatically smaller archive.
+    pub fn live_pages_unsafe<'b>(reader: &'b mut (dyn DatabaseReader + 'a)) -> Result<BTreeSet<u64>, Error> {
+        let meta = Self::pick_meta_unsafe(reader)?;
+
+        let mut live = BTreeSet::<u64>::new();
+        live.insert(0);
+        live.insert(1);
+        Self::collect_live_unsafe(reader, meta.main.root, &mut live)?;
+        Self::collect_live_unsafe(reader, meta.free.root, &mut live)?;
+
+        Ok(live)
+    }
+
+    fn collect_live_unsafe<'b>(
+        reader: &'b mut (dyn DatabaseReader + 'a),
+        pageno: u64,
+        live: &mut BTreeSet<u64>,
+    ) -> Result<(), Error> {
+        if !live.insert(pageno) {
+            return Ok(());
+        }
+
+        Self::seek_page_unsafe(reader, pageno as usize)?;
+        let head = reader.pos()?;
+
+        let _pageno = reader.read_word()?;
+        let _pad = reader.read_u16()?;
+        let flags = header::Flags::from_bits_truncate(reader.read_u16()?);
+        let free_lower = reader.read_u16()?;
+        let _free_upper = reader.read_u16()?;
+
+        if !flags.contains(header::Flags::BRANCH) {
+            return Ok(());
+        }
+
+        let dir_start = reader.pos()?;
+        let nptrs = (free_lower as usize).saturating_sub(dir_start - head) / 2;
+        let mut ptrs = Vec::with_capacity(nptrs);
+        for _ in 0..nptrs {
+            ptrs.push(reader.read_u16()?);
+        }
+
+        let mut children = Vec::<u64>::new();
+        for ptr in ptrs {
+            reader.seek(std::io::SeekFrom::Start((head + ptr as usize) as u64))?;
+            let data_len = reader.read_u32()? as usize;
+            let _node_flags = reader.read_u16()?;
+            let key_len = reader.read_u16()? as usize;
+            let _key = reader.read_exact(key_len)?;
+            let data = reader.read_exact(data_len)?;
+            match data.len() {
+                4 => children.push(u32::from_le_bytes(data.try_into().unwrap()) as u64),
+                8 => children.push(u64::from_le_bytes(data.try_into().unwrap())),
+                _ => {}
+            }
+        }
+
+        for child in children {
+            Self::collect_live_unsafe(reader, child, live)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write every live page as a `(pageno, zlib-compressed bytes)` record behind a small header
+    /// recording the word size and original `last_pgno`, so `unpack_unsafe` can rebuild a
+    /// byte-identical sparse file without having to walk the tree again.
+    pub fn pack_unsafe<'b, W: Write>(
+        reader: &'b mut (dyn DatabaseReader + 'a),
+        word_size: WordSize,
+        out: &mut W,
+    ) -> Result<(), Error> {
+        let meta = Self::pick_meta_unsafe(reader)?;
+        let live = Self::live_pages_unsafe(reader)?;
+
+        let word_byte: u8 = match word_size {
+            WordSize::Word32 => 32,
+            WordSize::Word64 => 64,
+        };
+
+        out.write_all(&PACK_MAGIC.to_le_bytes()).change_context(Error::default())?;
+        out.write_all(&[word_byte]).change_context(Error::default())?;
+        out.write_all(&meta.last_pgno.to_le_bytes()).change_context(Error::default())?;
+        out.write_all(&(live.len() as u64).to_le_bytes()).change_context(Error::default())?;
+
+        for pageno in live {
+            Self::seek_page_unsafe(reader, pageno as usize)?;
+            let raw = reader.read_exact(4096)?;
+
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&raw).change_context(Error::default())?;
+            let compressed = encoder.finish().change_context(Error::default())?;
+
+            out.write_all(&pageno.to_le_bytes()).change_context(Error::default())?;
+            out.write_all(&(compressed.len() as u32).to_le_bytes()).change_context(Error::default())?;
+            out.write_all(&compressed).change_context(Error::default())?;
+        }
+
+        Ok(())
+    }
+
+    /// Reconstruct a full sparse file from a pack stream, seeking to `pageno * 4096` and
+    /// inflating each record in turn.
+    pub fn unpack_unsafe<'b, R: Read, F: Write + Seek>(input: &mut R, out: &mut F) -> Result<(), Error> {
+        let mut magic = [0u8; 4];
+        input.read_exact(&mut magic).change_context(Error::default())?;
+        if u32::from_le_bytes(magic) != PACK_MAGIC {
+            return Err(Report::new(Error::default()).attach_printable("not a pack stream"));
+        }
+
+        let mut word_byte = [0u8; 1];
+        input.read_exact(&mut word_byte).change_context(Error::default())?;
+
+        let mut u64_buf = [0u8; 8];
+        input.read_exact(&mut u64_buf).change_context(Error::default())?;
+        let last_pgno = u64::from_le_bytes(u64_buf);
+
+        input.read_exact(&mut u64_buf).change_context(Error::default())?;
+        let npages = u64::from_le_bytes(u64_buf);
+
+        out.seek(std::io::SeekFrom::Start((last_pgno + 1) * 4096 - 1)).change_context(Error::default())?;
+        out.write_all(&[0u8]).change_context(Error::default())?;
+
+        for _ in 0..npages {
+            input.read_exact(&mut u64_buf).change_context(Error::default())?;
+            let pageno = u64::from_le_bytes(u64_buf);
+
+            let mut len_buf = [0u8; 4];
+            input.read_exact(&mut len_buf).change_context(Error::default())?;
+            let len = u32::from_le_bytes(len_buf) as usize;
+
+            let mut compressed = vec![0u8; len];
+            input.read_exact(&mut compressed).change_context(Error::default())?;
+
+            let mut decoder = ZlibDecoder::new(&compressed[..]);
+            let mut raw = Vec::new();
+            decoder.read_to_end(&mut raw).change_context(Error::default())?;
+
+            out.seek(std::io::SeekFrom::Start(pageno * 4096)).change_context(Error::default())?;
+            out.write_all(&raw).change_context(Error::default())?;
+        }
+
+        Ok(())
+    }
+}
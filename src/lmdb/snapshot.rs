@@ -0,0 +1,162 @@
+
+use std::io::BufRead;
+use std::io::Write;
+
+use error_stack::Report;
+use error_stack::Result;
+use error_stack::ResultExt;
+use serde::Deserialize;
+use serde::Serialize;
+
+use super::database::Database;
+use super::database::DatabaseWriter;
+use super::error::Error;
+use super::merge_policy::MergePolicy;
+use super::model;
+use super::WordSize;
+
+/// One line of the dump/restore format: a leading header describing the source database,
+/// followed by one line per key/value node. Newline-delimited JSON so the dumper can stream it
+/// instead of holding the whole database in memory; the restorer reads every node into memory
+/// before it builds the tree.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SnapshotLine {
+    Header { magic: u32, version: u32, mapsize: u64, word_size: u8 },
+    Node { key: Vec<u8>, data: Vec<u8>, flags: u16 },
+}
+
+/// Encode a `WordSize` the same way `pack.rs` does: as the bit width (32 or 64) rather than the
+/// enum discriminant, so the number in the snapshot file is self-explanatory.
+fn word_size_bits(word_size: WordSize) -> u8 {
+    match word_size {
+        WordSize::Word32 => 32,
+        WordSize::Word64 => 64,
+    }
+}
+
+fn word_size_from_bits(bits: u8) -> Result<WordSize, Error> {
+    match bits {
+        32 => Ok(WordSize::Word32),
+        64 => Ok(WordSize::Word64),
+        other => Err(Report::new(Error::default()).attach_printable(format!("unrecognised word size {} in snapshot header", other))),
+    }
+}
+
+pub struct SnapshotWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> SnapshotWriter<W> {
+    pub fn new(out: W) -> Self {
+        SnapshotWriter { out }
+    }
+
+    pub fn write_header(&mut self, meta: &model::Metadata, word_size: WordSize) -> Result<(), Error> {
+        self.write_line(&SnapshotLine::Header {
+            magic: meta.magic,
+            version: meta.version,
+            mapsize: meta.mapsize,
+            word_size: word_size_bits(word_size),
+        })
+    }
+
+    pub fn write_node(&mut self, node: &model::Node) -> Result<(), Error> {
+        self.write_line(&SnapshotLine::Node {
+            key: node.key.clone(),
+            data: node.data.clone(),
+            flags: node.flags,
+        })
+    }
+
+    fn write_line(&mut self, line: &SnapshotLine) -> Result<(), Error> {
+        let json = serde_json::to_string(line).change_context(Error::default())?;
+        writeln!(self.out, "{}", json).change_context(Error::default())?;
+        Ok(())
+    }
+}
+
+pub struct SnapshotReader<R: BufRead> {
+    lines: std::io::Lines<R>,
+}
+
+impl<R: BufRead> SnapshotReader<R> {
+    pub fn new(input: R) -> Self {
+        SnapshotReader { lines: input.lines() }
+    }
+
+    /// Read and parse the next line, or `None` once the snapshot is exhausted.
+    pub fn next_line(&mut self) -> Result<Option<SnapshotLine>, Error> {
+        match self.lines.next() {
+            None => Ok(None),
+            Some(line) => {
+                let line = line.change_context(Error::default())?;
+                let parsed = serde_json::from_str(&line).change_context(Error::default())?;
+                Ok(Some(parsed))
+            }
+        }
+    }
+
+    /// Read the leading header line every `SnapshotWriter`-produced snapshot starts with,
+    /// returning the word size it recorded. Must be called before `next_line` is used to read
+    /// the node lines that follow it.
+    pub fn read_header(&mut self) -> Result<WordSize, Error> {
+        match self.next_line()? {
+            Some(SnapshotLine::Header { word_size, .. }) => word_size_from_bits(word_size),
+            Some(other) => Err(Report::new(Error::default()).attach_printable(format!("expected a snapshot header, found {:?}", other))),
+            None => Err(Report::new(Error::default()).attach_printable("snapshot is empty, missing its header")),
+        }
+    }
+
+}
+
+/// Reconcile an explicit `--format` override against the word size `SnapshotReader::read_header`
+/// recorded: no override trusts the recording, a matching override is a no-op, and a mismatched
+/// override is rejected rather than silently producing a file at the wrong word size.
+pub fn resolve_word_size(explicit: Option<WordSize>, recorded: WordSize) -> Result<WordSize, Error> {
+    match explicit {
+        None => Ok(recorded),
+        Some(explicit) if explicit == recorded => Ok(explicit),
+        Some(explicit) => Err(Report::new(Error::default()).attach_printable(format!(
+            "--format {:?} does not match the snapshot's recorded word size {:?}",
+            explicit, recorded
+        ))),
+    }
+}
+
+impl<'a> Database<'a> {
+    /// Rebuild a fresh database from a snapshot. `entries`, `leaf_pages` and `last_pgno` are
+    /// re-derived from what was actually restored rather than trusted from the snapshot, so a
+    /// hand-edited snapshot can't leave the rebuilt file internally inconsistent.
+    pub fn restore_snapshot_unsafe<'b, R: BufRead>(
+        writer: &'b mut (dyn DatabaseWriter + 'a),
+        snapshot: &mut SnapshotReader<R>,
+        word_size: WordSize,
+        on_duplicate: MergePolicy,
+    ) -> Result<(), Error> {
+        let mut nodes = Vec::<model::Node>::new();
+
+        while let Some(line) = snapshot.next_line()? {
+            if let SnapshotLine::Node { key, data, flags } = line {
+                nodes.push(model::Node { flags, key, data });
+            }
+        }
+
+        let avg_key = nodes.iter().map(|n| n.key.len()).sum::<usize>().checked_div(nodes.len().max(1)).unwrap_or(0);
+        let avg_val = nodes.iter().map(|n| n.data.len()).sum::<usize>().checked_div(nodes.len().max(1)).unwrap_or(0);
+        let mapsize = super::metadata_size::estimate_mapsize(nodes.len() as u64, avg_key, avg_val);
+
+        let mut nodes = nodes.into_iter();
+        let main_db = Self::build_tree_unsafe(writer, || Ok(nodes.next()), 2, word_size, on_duplicate)?;
+
+        let (genesis, _) = Self::init_meta_unsafe(mapsize.max((main_db.root + 1) * 4096))?;
+        Self::write_meta_unsafe(writer, genesis.clone(), 0)?;
+
+        let mut meta = genesis;
+        meta.last_pgno = main_db.root.max(1);
+        meta.main = main_db;
+        Self::commit_meta_unsafe(writer, meta, 0)?;
+
+        Ok(())
+    }
+}
@@ -36,8 +36,53 @@ enum Commands {
 
         #[clap(short, long, value_name = "FILE")]
         output: std::path::PathBuf,
+
+        #[clap(long, value_enum, default_value_t = lmdb::MergePolicy::Append)]
+        on_duplicate: lmdb::MergePolicy,
+    },
+    Dump {
+        #[clap(short, long, value_name = "FILE")]
+        output: Option<std::path::PathBuf>,
+    },
+    Restore {
+        #[clap(short, long)]
+        format: Option<lmdb::WordSize>,
+
+        #[clap(short, long, value_name = "FILE")]
+        output: std::path::PathBuf,
+
+        #[clap(long, value_enum, default_value_t = lmdb::MergePolicy::Append)]
+        on_duplicate: lmdb::MergePolicy,
+    },
+    Repair {
+        #[clap(short, long, default_value = lmdb::WordSize::Word64)]
+        format: lmdb::WordSize,
+
+        #[clap(short, long, value_name = "FILE")]
+        output: std::path::PathBuf,
+    },
+    Check,
+    Pack {
+        #[clap(short, long, default_value = lmdb::WordSize::Word64)]
+        format: lmdb::WordSize,
+
+        #[clap(short, long, value_name = "FILE")]
+        output: std::path::PathBuf,
+    },
+    Unpack {
+        #[clap(short, long, value_name = "FILE")]
+        output: std::path::PathBuf,
+    },
+    MetadataSize {
+        #[clap(short, long)]
+        entries: u64,
+
+        #[clap(long, default_value_t = 16)]
+        avg_key_size: usize,
+
+        #[clap(long, default_value_t = 16)]
+        avg_val_size: usize,
     },
-    Dump,
 }
 
 fn main() {
@@ -56,29 +101,107 @@ fn main() {
     tracing::debug!("{:#?}", opts.clone());
 
     match opts.command {
-        Commands::Convert { format , output} => {
+        Commands::Convert { format , output, on_duplicate } => {
             println!("Converting to {:?}", format);
             let mut db_in = lmdb::Factory::open(opts.input.clone()).unwrap();
             let mut cur_in = db_in.read_cursor().unwrap();
-            
-            let mut db_out = lmdb::Factory::create(output.clone(), format).unwrap();
-            let mut cur_out = db_out.write_cursor().unwrap();
 
+            let mut nodes = Vec::new();
             while let Some(node) = cur_in.next().unwrap() {
-                cur_out.push_node(node).unwrap();
+                nodes.push(node);
             }
-            cur_out.commit().unwrap();
+            let avg_key = nodes.iter().map(|n| n.key.len()).sum::<usize>().checked_div(nodes.len().max(1)).unwrap_or(0);
+            let avg_val = nodes.iter().map(|n| n.data.len()).sum::<usize>().checked_div(nodes.len().max(1)).unwrap_or(0);
 
+            let mut db_out = lmdb::Factory::create(output.clone(), format).unwrap();
+            let mut writer = db_out.writer().unwrap();
+
+            let mut nodes = nodes.into_iter();
+            let main_db = lmdb::Database::build_tree_unsafe(&mut *writer, || Ok(nodes.next()), 2, format, on_duplicate).unwrap();
+
+            let mapsize = lmdb::metadata_size::estimate_mapsize(main_db.entries, avg_key, avg_val)
+                .max((main_db.root + 1) * 4096);
+            let (genesis, _) = lmdb::Database::init_meta_unsafe(mapsize).unwrap();
+            lmdb::Database::write_meta_unsafe(&mut *writer, genesis.clone(), 0).unwrap();
+
+            let mut meta = genesis;
+            meta.main = main_db;
+            meta.last_pgno = meta.main.root.max(1);
+            lmdb::Database::commit_meta_unsafe(&mut *writer, meta, 0).unwrap();
         }
-        Commands::Dump => {
+        Commands::Dump { output: None } => {
             let mut db = lmdb::Factory::open(opts.input.clone()).unwrap();
             let mut cur = db.read_cursor().unwrap();
             let mut i = 0;
-            while let Some(node) = cur.next().unwrap() {    
+            while let Some(node) = cur.next().unwrap() {
                 println!("#{}: {:#?}", i, node);
                 i+=1;
             }
         }
+        Commands::Dump { output: Some(output) } => {
+            let mut db = lmdb::Factory::open(opts.input.clone()).unwrap();
+            let meta = db.meta().unwrap();
+            let mut cur = db.read_cursor().unwrap();
+
+            let file = std::fs::File::create(output).unwrap();
+            let mut snapshot = lmdb::SnapshotWriter::new(std::io::BufWriter::new(file));
+            snapshot.write_header(&meta, db.word_size()).unwrap();
+            while let Some(node) = cur.next().unwrap() {
+                snapshot.write_node(&node).unwrap();
+            }
+        }
+        Commands::Restore { format, output, on_duplicate } => {
+            let file = std::fs::File::open(opts.input.clone()).unwrap();
+            let mut snapshot = lmdb::SnapshotReader::new(std::io::BufReader::new(file));
+
+            let recorded = snapshot.read_header().unwrap();
+            let format = lmdb::snapshot::resolve_word_size(format, recorded).unwrap();
+
+            let mut db_out = lmdb::Factory::create(output.clone(), format).unwrap();
+            let mut writer = db_out.writer().unwrap();
+            lmdb::Database::restore_snapshot_unsafe(&mut *writer, &mut snapshot, format, on_duplicate).unwrap();
+        }
+        Commands::Repair { format, output } => {
+            let file_len = std::fs::metadata(opts.input.clone()).unwrap().len();
+            let mut db_in = lmdb::Factory::open(opts.input.clone()).unwrap();
+            let mut reader = db_in.reader().unwrap();
+
+            let mut db_out = lmdb::Factory::create(output.clone(), format).unwrap();
+            let mut writer = db_out.writer().unwrap();
+
+            let meta = lmdb::Database::repair_unsafe(&mut *reader, &mut *writer, file_len / 4096, format).unwrap();
+            lmdb::Database::write_repaired_unsafe(&mut *writer, meta).unwrap();
+        }
+        Commands::MetadataSize { entries, avg_key_size, avg_val_size } => {
+            let pages = lmdb::metadata_size::estimate_pages(entries, avg_key_size, avg_val_size);
+            let mapsize = pages * 4096;
+            println!("{} pages ({} bytes)", pages, mapsize);
+        }
+        Commands::Pack { format, output } => {
+            let mut db = lmdb::Factory::open(opts.input.clone()).unwrap();
+            let mut reader = db.reader().unwrap();
+
+            let file = std::fs::File::create(output).unwrap();
+            let mut out = std::io::BufWriter::new(file);
+            lmdb::Database::pack_unsafe(&mut *reader, format, &mut out).unwrap();
+        }
+        Commands::Unpack { output } => {
+            let file = std::fs::File::open(opts.input.clone()).unwrap();
+            let mut input = std::io::BufReader::new(file);
+            let mut out = std::fs::File::create(output).unwrap();
+            lmdb::Database::unpack_unsafe(&mut input, &mut out).unwrap();
+        }
+        Commands::Check => {
+            let mut db = lmdb::Factory::open(opts.input.clone()).unwrap();
+            let mut reader = db.reader().unwrap();
+            match lmdb::Database::check_unsafe(&mut *reader) {
+                Ok(()) => println!("OK"),
+                Err(report) => {
+                    eprintln!("{:?}", report);
+                    std::process::exit(1);
+                }
+            }
+        }
     }
     
 }
\ No newline at end of file